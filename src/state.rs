@@ -1,8 +1,170 @@
+// Checkpoint encoding pulls in `rmp_serde` for the default MessagePack
+// format and `serde_json` for `CheckpointFormat::Json` plus the manifest
+// sidecar; both need to be declared as dependencies in Cargo.toml.
 use crate::error;
 use serde::{Deserialize, Serialize};
-use std::fs::{create_dir_all, File};
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
 use std::io::prelude::*;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Number of journal entries to accumulate between full `State` snapshots
+/// before folding them into a fresh checkpoint, mirroring Bayou's
+/// `KEEP_STATE_EVERY`.
+pub const KEEP_STATE_EVERY: u64 = 100;
+
+/// The `State` layout that manifests are validated against. Bump this
+/// whenever a change to `State` would make an old checkpoint decode into
+/// the wrong shape rather than fail cleanly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// On-disk encoding for a checkpoint's data file.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckpointFormat {
+    /// Compact binary encoding via `rmp_serde`. The default.
+    MessagePack,
+    /// Human-readable encoding via `serde_json`, useful for debugging and
+    /// ad-hoc inspection of a checkpoint.
+    Json,
+}
+
+impl Default for CheckpointFormat {
+    fn default() -> Self {
+        CheckpointFormat::MessagePack
+    }
+}
+
+impl CheckpointFormat {
+    fn encode(self, state: &State) -> Vec<u8> {
+        match self {
+            CheckpointFormat::MessagePack => rmp_serde::to_vec_named(state).unwrap(),
+            CheckpointFormat::Json => serde_json::to_vec_pretty(state).unwrap(),
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Result<State, error::Error> {
+        match self {
+            CheckpointFormat::MessagePack => rmp_serde::from_read_ref(bytes)
+                .map_err(|e| error::Error::InvalidCheckpoint(format!("{}", e))),
+            CheckpointFormat::Json => serde_json::from_slice(bytes)
+                .map_err(|e| error::Error::InvalidCheckpoint(format!("{}", e))),
+        }
+    }
+}
+
+/// Guards `write_checkpoint` against firing too often, independent of
+/// `CheckpointMode`: even when the mode says a checkpoint is due, the
+/// write is skipped unless both thresholds have elapsed since the last
+/// successful write, per aerogramme's min-interval/min-ops guard.
+/// `min_interval` is real wall-clock seconds, not simulation time — it
+/// bounds how often this process actually touches disk, independent of
+/// how fast or slow simulation time is advancing.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CheckpointThrottle {
+    pub min_interval: f64,
+    pub min_iterations: u64,
+}
+
+impl Default for CheckpointThrottle {
+    fn default() -> Self {
+        Self { min_interval: 0.0, min_iterations: 0 }
+    }
+}
+
+impl CheckpointThrottle {
+    /// `elapsed_wall_clock` is the real time since the last successful
+    /// checkpoint write, or `None` if this process hasn't written one yet
+    /// (in which case there is no previous write to measure from, and
+    /// neither guard applies).
+    fn allows(&self, iteration: u64, last_iteration: u64, elapsed_wall_clock: Option<Duration>) -> bool {
+        match elapsed_wall_clock {
+            None => true,
+            Some(elapsed) => {
+                elapsed.as_secs_f64() >= self.min_interval && iteration - last_iteration >= self.min_iterations
+            }
+        }
+    }
+}
+
+/// Bounds how many `chkpt.*.sf` files accumulate on disk: the most recent
+/// `keep_recent` checkpoints are always kept, plus every `milestone_every`th
+/// checkpoint number (if set) is kept indefinitely as a long-term anchor.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    pub keep_recent: u64,
+    pub milestone_every: Option<u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self { keep_recent: u64::MAX, milestone_every: None }
+    }
+}
+
+impl RetentionPolicy {
+    fn is_kept(&self, number: u64, latest: u64) -> bool {
+        let age = latest.saturating_sub(number);
+        (self.keep_recent > 0 && age < self.keep_recent)
+            || self.milestone_every.map_or(false, |m| m != 0 && number % m == 0)
+    }
+
+    /// Deletes every checkpoint (and its manifest) under `outdir` that this
+    /// policy does not keep, given that `latest` was just written.
+    fn enforce(&self, outdir: &str, latest: u64) -> Result<(), error::Error> {
+        for entry in std::fs::read_dir(outdir).map_err(error::Error::IOError)?.flatten() {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let number = match name.strip_prefix("chkpt.").and_then(|s| s.strip_suffix(".sf")).and_then(|s| s.parse().ok()) {
+                Some(number) => number,
+                None => continue,
+            };
+            if !self.is_kept(number, latest) {
+                let path = format!("{}/{}", outdir, name);
+                remove_file(CheckpointManifest::filename(&path)).ok();
+                remove_file(&path).map_err(error::Error::IOError)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A small, self-describing sidecar written next to each checkpoint so
+/// `from_checkpoint` knows how to decode it without guessing, and can
+/// reject a checkpoint from an incompatible `State` layout with a clear
+/// error instead of an opaque serde failure.
+#[derive(Serialize, Deserialize)]
+struct CheckpointManifest {
+    format: CheckpointFormat,
+    schema_version: u32,
+    setup_name: String,
+    primitive_len: usize,
+}
+
+impl CheckpointManifest {
+    fn filename(checkpoint_filename: &str) -> String {
+        format!("{}.manifest.json", checkpoint_filename)
+    }
+
+    fn write(&self, checkpoint_filename: &str) -> Result<(), error::Error> {
+        let bytes = serde_json::to_vec_pretty(self).unwrap();
+        std::fs::write(Self::filename(checkpoint_filename), bytes).map_err(error::Error::IOError)
+    }
+
+    /// Reads the manifest beside `checkpoint_filename`, if one was written.
+    /// Older checkpoints predating this feature have no manifest.
+    fn read(checkpoint_filename: &str) -> Result<Option<Self>, error::Error> {
+        match std::fs::read(Self::filename(checkpoint_filename)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| error::Error::InvalidCheckpoint(format!("{}", e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(error::Error::IOError(e)),
+        }
+    }
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RecurringTask {
@@ -20,6 +182,214 @@ impl RecurringTask {
     }
 }
 
+/// Decides whether a checkpoint should be written on a given step, modeled
+/// on argmin's `CheckpointingFrequency`.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum CheckpointMode {
+    /// Never checkpoint.
+    Never,
+    /// Checkpoint on every call.
+    Always,
+    /// Checkpoint once every `n` iterations.
+    EveryIterations(u64),
+    /// Checkpoint once every `interval` units of simulation time.
+    EveryTime(f64),
+    /// Checkpoint whenever either the iteration or time condition fires.
+    EveryIterationsOrTime { iterations: u64, interval: f64 },
+}
+
+impl CheckpointMode {
+    /// Returns `true` if a checkpoint should be written given the current
+    /// iteration count, simulation time, and the iteration/time of the last
+    /// checkpoint that was actually written.
+    fn should_checkpoint(
+        &self,
+        iteration: u64,
+        time: f64,
+        last_iteration: u64,
+        last_time: f64,
+    ) -> bool {
+        match self {
+            CheckpointMode::Never => false,
+            CheckpointMode::Always => true,
+            CheckpointMode::EveryIterations(n) => iteration - last_iteration >= *n,
+            CheckpointMode::EveryTime(interval) => time - last_time >= *interval,
+            CheckpointMode::EveryIterationsOrTime { iterations, interval } => {
+                iteration - last_iteration >= *iterations || time - last_time >= *interval
+            }
+        }
+    }
+}
+
+impl Default for CheckpointMode {
+    fn default() -> Self {
+        CheckpointMode::EveryTime(0.0)
+    }
+}
+
+/// A compact record of the change to `primitive` between two steps: the
+/// contiguous sub-block `[offset, offset + values.len())` that differs from
+/// the last full snapshot or journal entry, plus the iteration/time it was
+/// recorded at.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub iteration: u64,
+    pub time: f64,
+    pub offset: usize,
+    pub values: Vec<f64>,
+}
+
+impl JournalEntry {
+    /// Builds the entry describing how `primitive` changed from `previous`.
+    /// Assumes both slices have the same length.
+    fn diff(previous: &[f64], primitive: &[f64], iteration: u64, time: f64) -> Self {
+        let start = previous
+            .iter()
+            .zip(primitive)
+            .position(|(a, b)| a != b)
+            .unwrap_or(primitive.len());
+        let end = previous
+            .iter()
+            .zip(primitive)
+            .rposition(|(a, b)| a != b)
+            .map_or(start, |i| i + 1);
+
+        JournalEntry {
+            iteration,
+            time,
+            offset: start,
+            values: primitive[start..end].to_vec(),
+        }
+    }
+
+    fn apply(&self, primitive: &mut [f64]) {
+        primitive[self.offset..self.offset + self.values.len()].copy_from_slice(&self.values);
+    }
+}
+
+/// An append-only log of `JournalEntry` records, written between full
+/// `State` snapshots so that routine steps don't pay the cost of dumping
+/// the entire `primitive` array, following the Bayou approach.
+struct Journal;
+
+impl Journal {
+    fn filename(outdir: &str, checkpoint_number: u64) -> String {
+        format!("{}/journal.{:04}.sf", outdir, checkpoint_number)
+    }
+
+    /// Appends `entry` to the journal for `checkpoint_number`, creating the
+    /// file if this is the first entry since the last full snapshot. The
+    /// caller tracks how many entries the journal now holds (see
+    /// `State::journal_len`) rather than this re-reading the log, since the
+    /// whole point of journaling is to avoid paying a full-log decode on
+    /// every cheap incremental write.
+    fn append(outdir: &str, checkpoint_number: u64, entry: &JournalEntry) -> Result<(), error::Error> {
+        let filename = Self::filename(outdir, checkpoint_number);
+        let bytes = rmp_serde::to_vec_named(entry).unwrap();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&filename)
+            .map_err(error::Error::IOError)?;
+        file.write_all(&(bytes.len() as u64).to_le_bytes()).map_err(error::Error::IOError)?;
+        file.write_all(&bytes).map_err(error::Error::IOError)?;
+        file.sync_all().map_err(error::Error::IOError)
+    }
+
+    /// Loads every entry appended to the journal for `checkpoint_number`,
+    /// in write order. Returns an empty vector if no journal has been
+    /// started since that snapshot.
+    fn load(outdir: &str, checkpoint_number: u64) -> Result<Vec<JournalEntry>, error::Error> {
+        let filename = Self::filename(outdir, checkpoint_number);
+        let mut file = match File::open(&filename) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(error::Error::IOError(e)),
+        };
+
+        let mut entries = Vec::new();
+        loop {
+            let mut len_bytes = [0u8; 8];
+            match file.read_exact(&mut len_bytes) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(error::Error::IOError(e)),
+            }
+            let mut bytes = vec![0u8; u64::from_le_bytes(len_bytes) as usize];
+            file.read_exact(&mut bytes).map_err(error::Error::IOError)?;
+            let entry: JournalEntry = rmp_serde::from_read_ref(&bytes)
+                .map_err(|e| error::Error::InvalidCheckpoint(format!("{}", e)))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Deletes the journal for `checkpoint_number`, once its entries have
+    /// been folded into a fresh full checkpoint.
+    fn delete(outdir: &str, checkpoint_number: u64) -> Result<(), error::Error> {
+        match remove_file(Self::filename(outdir, checkpoint_number)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(error::Error::IOError(e)),
+        }
+    }
+}
+
+/// A lightweight, frequently-updated record of how far a run has gotten,
+/// decoupled from the full `State` snapshot so a resume path can learn the
+/// watermark and which checkpoint to load without deserializing a
+/// multi-gigabyte `primitive` array, modeled on Sui's progress store.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProgressStore {
+    pub iteration: u64,
+    pub time: f64,
+    pub checkpoint_number: u64,
+    pub checkpoint_filename: String,
+}
+
+impl ProgressStore {
+    fn filename(outdir: &str) -> String {
+        format!("{}/progress.sf", outdir)
+    }
+
+    /// Writes the progress watermark, atomically, the same way
+    /// `write_full_checkpoint` does for full snapshots.
+    pub fn save(&self, outdir: &str) -> Result<(), error::Error> {
+        create_dir_all(outdir).map_err(error::Error::IOError)?;
+        let bytes = rmp_serde::to_vec_named(self).unwrap();
+        let filename = Self::filename(outdir);
+        let tmp_filename = format!("{}.tmp", filename);
+        let mut file = File::create(&tmp_filename).map_err(error::Error::IOError)?;
+        file.write_all(&bytes).map_err(error::Error::IOError)?;
+        file.flush().map_err(error::Error::IOError)?;
+        file.sync_all().map_err(error::Error::IOError)?;
+        rename(&tmp_filename, &filename).map_err(error::Error::IOError)
+    }
+
+    /// Reads the progress watermark under `outdir`, if a run has gotten far
+    /// enough to have written one.
+    pub fn load(outdir: &str) -> Result<Option<Self>, error::Error> {
+        match File::open(Self::filename(outdir)) {
+            Ok(mut file) => {
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes).map_err(error::Error::IOError)?;
+                rmp_serde::from_read_ref(&bytes)
+                    .map(Some)
+                    .map_err(|e| error::Error::InvalidCheckpoint(format!("{}", e)))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(error::Error::IOError(e)),
+        }
+    }
+
+    /// The iteration/time already completed and persisted, below which a
+    /// resumed run can skip work it already did.
+    pub fn min_watermark(&self) -> (u64, f64) {
+        (self.iteration, self.time)
+    }
+}
+
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct State {
     pub setup_name: String,
@@ -28,37 +398,454 @@ pub struct State {
     pub time: f64,
     pub iteration: u64,
     pub checkpoint: RecurringTask,
+    #[serde(default)]
+    pub checkpoint_mode: CheckpointMode,
+    #[serde(default)]
+    pub checkpoint_format: CheckpointFormat,
+    #[serde(default)]
+    pub checkpoint_throttle: CheckpointThrottle,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    #[serde(default)]
+    last_checkpoint_iteration: u64,
+    #[serde(default)]
+    last_checkpoint_time: f64,
+    /// Number of entries appended to the journal since the last full
+    /// snapshot. Tracked here instead of re-reading the journal on every
+    /// `write_journal_entry` call, which would be O(n^2) over a run.
+    #[serde(default)]
+    journal_len: u64,
+    /// Real time of the last successful checkpoint write, for
+    /// `checkpoint_throttle`'s wall-clock guard. Not persisted: a resumed
+    /// process has written nothing yet, so its throttle starts fresh.
+    #[serde(skip)]
+    last_checkpoint_wall_clock: Option<Instant>,
+}
+
+/// Why `read_checkpoint_file` failed: distinguishes a truncated/corrupt data
+/// file, which `from_checkpoint` should fall back to an earlier checkpoint
+/// for, from a schema-version mismatch, which no sibling checkpoint can fix
+/// and should fail fast with its specific message instead of being buried
+/// under a generic "no valid checkpoint found" from the fallback search.
+enum CheckpointReadError {
+    Corrupt(error::Error),
+    SchemaMismatch(error::Error),
 }
 
 impl State {
     pub fn from_checkpoint(filename: &str, new_parameters: &str) -> Result<State, error::Error> {
-        let mut f = File::open(filename).map_err(error::Error::IOError)?;
+        let mut state = match Self::read_checkpoint_file(filename) {
+            Ok(state) => state,
+            Err(CheckpointReadError::SchemaMismatch(e)) => return Err(e),
+            Err(CheckpointReadError::Corrupt(e)) => {
+                println!("{} ({}), falling back to an earlier checkpoint", e, filename);
+                Self::read_fallback_checkpoint(filename)?
+            }
+        };
 
-        let mut bytes = Vec::new();
-        f.read_to_end(&mut bytes).map_err(error::Error::IOError)?;
-
-        let mut state: State = rmp_serde::from_read_ref(&bytes)
-            .map_err(|e| error::Error::InvalidCheckpoint(format!("{}", e)))?;
+        state.replay_journal(filename)?;
 
         state.parameters += ":";
         state.parameters += new_parameters;
+        Ok(state)
+    }
+
+    /// Replays the journal entries trailing the full snapshot `filename` was
+    /// loaded from, reconstructing the exact `primitive`, `time`, and
+    /// `iteration` at the point the run was last checkpointed or journaled.
+    fn replay_journal(&mut self, filename: &str) -> Result<(), error::Error> {
+        let path = Path::new(filename);
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        let outdir = dir.to_string_lossy();
+
+        let entries = Journal::load(&outdir, self.checkpoint.number)?;
+        for entry in &entries {
+            entry.apply(&mut self.primitive);
+            self.iteration = entry.iteration;
+            self.time = entry.time;
+        }
+        self.journal_len = entries.len() as u64;
+        Ok(())
+    }
+
+    /// Reads and decodes a single checkpoint file, with no fallback. Uses
+    /// the sidecar manifest to pick the decoder, to reject a checkpoint
+    /// written by an incompatible `State` schema, and to catch a decoded
+    /// `primitive` whose length disagrees with what the manifest recorded;
+    /// falls back to the legacy hardcoded MessagePack decoding for
+    /// checkpoints with no manifest.
+    fn read_checkpoint_file(filename: &str) -> Result<State, CheckpointReadError> {
+        let mut f = File::open(filename).map_err(|e| CheckpointReadError::Corrupt(error::Error::IOError(e)))?;
+
+        let mut bytes = Vec::new();
+        f.read_to_end(&mut bytes).map_err(|e| CheckpointReadError::Corrupt(error::Error::IOError(e)))?;
+
+        let manifest = match CheckpointManifest::read(filename).map_err(CheckpointReadError::Corrupt)? {
+            Some(manifest) if manifest.schema_version != SCHEMA_VERSION => {
+                return Err(CheckpointReadError::SchemaMismatch(error::Error::InvalidCheckpoint(format!(
+                    "{} was written by schema version {}, expected {}",
+                    filename, manifest.schema_version, SCHEMA_VERSION
+                ))));
+            }
+            manifest => manifest,
+        };
+        let format = manifest.as_ref().map_or(CheckpointFormat::MessagePack, |m| m.format);
+        let state = format.decode(&bytes).map_err(CheckpointReadError::Corrupt)?;
+
+        if let Some(manifest) = &manifest {
+            if manifest.primitive_len != state.primitive.len() {
+                return Err(CheckpointReadError::Corrupt(error::Error::InvalidCheckpoint(format!(
+                    "{} manifest declares primitive_len {}, decoded {}",
+                    filename, manifest.primitive_len, state.primitive.len()
+                ))));
+            }
+        }
 
         println!("read {}", filename);
         Ok(state)
     }
 
+    /// Looks in the directory containing `failed_filename` for the
+    /// highest-numbered `chkpt.*.sf` file other than `failed_filename` that
+    /// decodes cleanly, and returns it. Used when the requested checkpoint
+    /// is truncated or corrupt, e.g. from a process killed mid-write.
+    fn read_fallback_checkpoint(failed_filename: &str) -> Result<State, error::Error> {
+        let path = Path::new(failed_filename);
+        let dir = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+
+        let mut candidates: Vec<(u64, String)> = std::fs::read_dir(dir)
+            .map_err(error::Error::IOError)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let number: u64 = name.strip_prefix("chkpt.")?.strip_suffix(".sf")?.parse().ok()?;
+                let full_path = dir.join(&name);
+                if full_path == path {
+                    return None;
+                }
+                Some((number, full_path.to_string_lossy().into_owned()))
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0));
+
+        for (_, candidate) in candidates {
+            if let Ok(state) = Self::read_checkpoint_file(&candidate) {
+                return Ok(state);
+            }
+        }
+
+        Err(error::Error::InvalidCheckpoint(format!(
+            "no valid checkpoint found in {}",
+            dir.display()
+        )))
+    }
+
+    /// Writes a checkpoint if `self.checkpoint_mode` says one is due and
+    /// `self.checkpoint_throttle` allows it, consulting iteration count and
+    /// simulation time since the last checkpoint that was actually written.
+    ///
+    /// The caller must invoke this on every step, not only when some
+    /// external time cadence (e.g. a `RecurringTask`) fires: `checkpoint_mode`
+    /// makes its own iteration/time decision internally, and gating the call
+    /// itself on a cadence check defeats `EveryIterations`/`Always`/
+    /// `EveryIterationsOrTime`, which would then only ever get a chance to
+    /// fire on the cadence's schedule. `checkpoint_interval` is passed
+    /// through to `RecurringTask::next` purely for bookkeeping in the
+    /// written checkpoint's metadata and plays no part in the due-or-not
+    /// decision.
     pub fn write_checkpoint(
         &mut self,
         checkpoint_interval: f64,
         outdir: &str,
     ) -> Result<(), error::Error> {
+        let elapsed_wall_clock = self.last_checkpoint_wall_clock.map(|t| t.elapsed());
+        let due = self.checkpoint_mode.should_checkpoint(
+            self.iteration,
+            self.time,
+            self.last_checkpoint_iteration,
+            self.last_checkpoint_time,
+        ) && self.checkpoint_throttle.allows(self.iteration, self.last_checkpoint_iteration, elapsed_wall_clock);
+        if !due {
+            return Ok(());
+        }
+        self.write_full_checkpoint(checkpoint_interval, outdir)
+    }
+
+    /// Writes the lightweight `ProgressStore` watermark for this state,
+    /// independent of `checkpoint_mode`/`checkpoint_throttle`. Intended to
+    /// be called every step or every few steps, since it's cheap compared
+    /// to a full checkpoint.
+    pub fn save_progress(&self, outdir: &str) -> Result<(), error::Error> {
+        let (checkpoint_number, checkpoint_filename) = match self.checkpoint.number.checked_sub(1) {
+            Some(last) => (last, format!("{}/chkpt.{:04}.sf", outdir, last)),
+            None => (0, String::new()),
+        };
+        ProgressStore {
+            iteration: self.iteration,
+            time: self.time,
+            checkpoint_number,
+            checkpoint_filename,
+        }
+        .save(outdir)
+    }
+
+    /// Appends an incremental record of the change to `primitive` since
+    /// `previous_primitive` to the journal, rather than writing a full
+    /// snapshot. Once the journal accumulates `KEEP_STATE_EVERY` entries it
+    /// is folded into a fresh full checkpoint via `compact`.
+    pub fn write_journal_entry(
+        &mut self,
+        previous_primitive: &[f64],
+        outdir: &str,
+    ) -> Result<(), error::Error> {
+        create_dir_all(outdir).map_err(error::Error::IOError)?;
+        let entry = JournalEntry::diff(previous_primitive, &self.primitive, self.iteration, self.time);
+        let checkpoint_number = self.checkpoint.number;
+        Journal::append(outdir, checkpoint_number, &entry)?;
+        self.journal_len += 1;
+
+        if self.journal_len >= KEEP_STATE_EVERY {
+            self.compact(outdir)?;
+        }
+        Ok(())
+    }
+
+    /// Folds the journal trailing the current checkpoint into a fresh full
+    /// `State` snapshot, then deletes the superseded journal file.
+    pub fn compact(&mut self, outdir: &str) -> Result<(), error::Error> {
+        let checkpoint_number = self.checkpoint.number;
+        self.write_full_checkpoint(0.0, outdir)?;
+        Journal::delete(outdir, checkpoint_number)
+    }
+
+    /// Unconditionally writes a full snapshot of `self`, advancing the
+    /// checkpoint counter and recording it as the last checkpoint written.
+    fn write_full_checkpoint(&mut self, checkpoint_interval: f64, outdir: &str) -> Result<(), error::Error> {
         self.checkpoint.next(checkpoint_interval);
+        self.last_checkpoint_iteration = self.iteration;
+        self.last_checkpoint_time = self.time;
+        self.last_checkpoint_wall_clock = Some(Instant::now());
+        self.journal_len = 0;
         create_dir_all(outdir).map_err(error::Error::IOError)?;
-        let bytes = rmp_serde::to_vec_named(self).unwrap();
+        let bytes = self.checkpoint_format.encode(self);
+
+        // Write to a temporary file and rename into place so a process
+        // killed mid-write never leaves a truncated checkpoint behind:
+        // rename is atomic on the same filesystem.
         let filename = format!("{}/chkpt.{:04}.sf", outdir, self.checkpoint.number - 1);
-        let mut file = File::create(&filename).unwrap();
-        file.write_all(&bytes).unwrap();
+        let tmp_filename = format!("{}.tmp", filename);
+        let mut file = File::create(&tmp_filename).map_err(error::Error::IOError)?;
+        file.write_all(&bytes).map_err(error::Error::IOError)?;
+        file.flush().map_err(error::Error::IOError)?;
+        file.sync_all().map_err(error::Error::IOError)?;
+        rename(&tmp_filename, &filename).map_err(error::Error::IOError)?;
+
+        CheckpointManifest {
+            format: self.checkpoint_format,
+            schema_version: SCHEMA_VERSION,
+            setup_name: self.setup_name.clone(),
+            primitive_len: self.primitive.len(),
+        }
+        .write(&filename)?;
+        self.retention.enforce(outdir, self.checkpoint.number - 1)?;
+
         println!("write {}", filename);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn temp_outdir() -> String {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("sailfish_state_test_{}_{}", std::process::id(), n));
+        dir.to_string_lossy().into_owned()
+    }
+
+    fn new_state(primitive: Vec<f64>) -> State {
+        State {
+            setup_name: "test".to_string(),
+            parameters: "".to_string(),
+            primitive,
+            time: 0.0,
+            iteration: 0,
+            checkpoint: RecurringTask::new(),
+            checkpoint_mode: CheckpointMode::default(),
+            checkpoint_format: CheckpointFormat::default(),
+            checkpoint_throttle: CheckpointThrottle::default(),
+            retention: RetentionPolicy::default(),
+            last_checkpoint_iteration: 0,
+            last_checkpoint_time: 0.0,
+            journal_len: 0,
+            last_checkpoint_wall_clock: None,
+        }
+    }
+
+    #[test]
+    fn resume_replays_journal_entries_past_the_last_full_snapshot() {
+        let outdir = temp_outdir();
+        let mut state = new_state(vec![1.0, 2.0, 3.0, 4.0]);
+
+        // A full snapshot, then journaled steps that are never folded into
+        // another full checkpoint.
+        state.write_checkpoint(1.0, &outdir).unwrap();
+
+        let previous = state.primitive.clone();
+        state.primitive[1] = 20.0;
+        state.iteration = 1;
+        state.time = 1.0;
+        state.write_journal_entry(&previous, &outdir).unwrap();
+
+        let previous = state.primitive.clone();
+        state.primitive[3] = 40.0;
+        state.iteration = 2;
+        state.time = 2.0;
+        state.write_journal_entry(&previous, &outdir).unwrap();
+
+        let resumed = State::from_checkpoint(&format!("{}/chkpt.0000.sf", outdir), "").unwrap();
+
+        assert_eq!(resumed.primitive, vec![1.0, 20.0, 3.0, 40.0]);
+        assert_eq!(resumed.iteration, 2);
+        assert_eq!(resumed.time, 2.0);
+
+        std::fs::remove_dir_all(&outdir).ok();
+    }
+
+    #[test]
+    fn from_checkpoint_falls_back_to_an_earlier_checkpoint_when_the_latest_is_corrupt() {
+        let outdir = temp_outdir();
+        let mut state = new_state(vec![1.0, 2.0]);
+        state.checkpoint_mode = CheckpointMode::Always;
+
+        state.iteration = 1;
+        state.write_checkpoint(1.0, &outdir).unwrap(); // chkpt.0000.sf
+
+        state.primitive = vec![9.0, 9.0];
+        state.iteration = 2;
+        state.write_checkpoint(1.0, &outdir).unwrap(); // chkpt.0001.sf
+
+        // Simulate a process killed mid-write: the latest checkpoint's data
+        // file is present but its contents don't decode.
+        std::fs::write(format!("{}/chkpt.0001.sf", outdir), b"not a valid checkpoint").unwrap();
+
+        let resumed = State::from_checkpoint(&format!("{}/chkpt.0001.sf", outdir), "").unwrap();
+
+        assert_eq!(resumed.primitive, vec![1.0, 2.0]);
+        assert_eq!(resumed.iteration, 1);
+
+        std::fs::remove_dir_all(&outdir).ok();
+    }
+
+    #[test]
+    fn json_checkpoint_format_round_trips() {
+        let outdir = temp_outdir();
+        let mut state = new_state(vec![1.0, 2.0, 3.0]);
+        state.checkpoint_format = CheckpointFormat::Json;
+        state.iteration = 5;
+        state.time = 2.5;
+        state.write_checkpoint(1.0, &outdir).unwrap();
+
+        let resumed = State::from_checkpoint(&format!("{}/chkpt.0000.sf", outdir), "").unwrap();
+
+        assert_eq!(resumed.primitive, vec![1.0, 2.0, 3.0]);
+        assert_eq!(resumed.iteration, 5);
+        assert_eq!(resumed.time, 2.5);
+
+        std::fs::remove_dir_all(&outdir).ok();
+    }
+
+    #[test]
+    fn from_checkpoint_fails_fast_on_schema_version_mismatch_instead_of_falling_back() {
+        let outdir = temp_outdir();
+        let mut state = new_state(vec![1.0]);
+        state.write_checkpoint(1.0, &outdir).unwrap();
+
+        let manifest_path = format!("{}/chkpt.0000.sf.manifest.json", outdir);
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        manifest["schema_version"] = serde_json::json!(SCHEMA_VERSION + 1);
+        std::fs::write(&manifest_path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        let err = State::from_checkpoint(&format!("{}/chkpt.0000.sf", outdir), "").unwrap_err();
+
+        assert!(matches!(err, error::Error::InvalidCheckpoint(_)));
+        assert!(format!("{}", err).contains("schema version"));
+
+        std::fs::remove_dir_all(&outdir).ok();
+    }
+
+    #[test]
+    fn checkpoint_throttle_blocks_writes_below_min_iterations() {
+        let outdir = temp_outdir();
+        let mut state = new_state(vec![1.0]);
+        state.checkpoint_mode = CheckpointMode::Always;
+        state.checkpoint_throttle = CheckpointThrottle { min_interval: 0.0, min_iterations: 10 };
+
+        state.write_checkpoint(1.0, &outdir).unwrap();
+        assert!(Path::new(&format!("{}/chkpt.0000.sf", outdir)).exists());
+
+        // Mode says a checkpoint is due, but the throttle's iteration
+        // threshold hasn't elapsed since the last write.
+        state.iteration = 1;
+        state.write_checkpoint(1.0, &outdir).unwrap();
+        assert!(!Path::new(&format!("{}/chkpt.0001.sf", outdir)).exists());
+
+        state.iteration = 10;
+        state.write_checkpoint(1.0, &outdir).unwrap();
+        assert!(Path::new(&format!("{}/chkpt.0001.sf", outdir)).exists());
+
+        std::fs::remove_dir_all(&outdir).ok();
+    }
+
+    #[test]
+    fn retention_policy_deletes_old_checkpoints_but_keeps_recent_and_milestones() {
+        let outdir = temp_outdir();
+        let mut state = new_state(vec![1.0]);
+        state.checkpoint_mode = CheckpointMode::Always;
+        state.retention = RetentionPolicy { keep_recent: 2, milestone_every: Some(2) };
+
+        for i in 0..5 {
+            state.iteration = i;
+            state.write_checkpoint(1.0, &outdir).unwrap();
+        }
+        // Checkpoints 0..=4 were written. keep_recent=2 keeps {3, 4};
+        // milestone_every=2 additionally keeps {0, 2}; only 1 is dropped.
+        let exists = |n: u64| Path::new(&format!("{}/chkpt.{:04}.sf", outdir, n)).exists();
+        assert!(exists(0));
+        assert!(!exists(1));
+        assert!(exists(2));
+        assert!(exists(3));
+        assert!(exists(4));
+
+        std::fs::remove_dir_all(&outdir).ok();
+    }
+
+    #[test]
+    fn progress_store_save_load_round_trips_and_reports_watermark() {
+        let outdir = temp_outdir();
+        assert!(ProgressStore::load(&outdir).unwrap().is_none());
+
+        let mut state = new_state(vec![1.0, 2.0]);
+        state.checkpoint_mode = CheckpointMode::Always;
+        state.write_checkpoint(1.0, &outdir).unwrap(); // chkpt.0000.sf
+
+        state.iteration = 7;
+        state.time = 3.5;
+        state.save_progress(&outdir).unwrap();
+
+        let progress = ProgressStore::load(&outdir).unwrap().unwrap();
+
+        assert_eq!(progress.min_watermark(), (7, 3.5));
+        assert_eq!(progress.checkpoint_number, 0);
+        assert_eq!(progress.checkpoint_filename, format!("{}/chkpt.0000.sf", outdir));
+
+        std::fs::remove_dir_all(&outdir).ok();
+    }
+}